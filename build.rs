@@ -0,0 +1,8 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // A vendored protoc, so building doesn't need one installed.
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_build::compile_protos("proto/shortener.proto")?;
+    // `sqlx::migrate!` embeds the migrations, but doesn't notice new files.
+    println!("cargo:rerun-if-changed=migrations");
+    Ok(())
+}
@@ -0,0 +1,62 @@
+-- The schema as the inline migrations left it. Every statement is idempotent,
+-- so databases set up before versioned migrations adopt it unchanged.
+--
+-- Table names are placeholders filled in from SHORTENER_TABLE: {urls},
+-- {clicks} and {audit_log}.
+
+CREATE TABLE IF NOT EXISTS {urls} (
+    id CHAR(6) PRIMARY KEY,
+    url TEXT NOT NULL UNIQUE
+);
+ALTER TABLE {urls} ADD COLUMN IF NOT EXISTS max_uses BIGINT;
+ALTER TABLE {urls} ADD COLUMN IF NOT EXISTS uses BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE {urls} ADD COLUMN IF NOT EXISTS created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+ALTER TABLE {urls} ADD COLUMN IF NOT EXISTS expires_at TIMESTAMPTZ;
+ALTER TABLE {urls} ADD COLUMN IF NOT EXISTS owner TEXT;
+
+CREATE TABLE IF NOT EXISTS {clicks} (
+    id BIGSERIAL PRIMARY KEY,
+    url_id CHAR(6) NOT NULL REFERENCES {urls}(id) ON DELETE CASCADE ON UPDATE CASCADE,
+    visited_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS {clicks}_url_id_visited_at ON {clicks} (url_id, visited_at);
+
+ALTER TABLE {urls} ADD COLUMN IF NOT EXISTS namespace TEXT NOT NULL DEFAULT '';
+ALTER TABLE {clicks} ADD COLUMN IF NOT EXISTS namespace TEXT NOT NULL DEFAULT '';
+ALTER TABLE {urls} DROP CONSTRAINT IF EXISTS {urls}_pkey CASCADE;
+ALTER TABLE {urls} DROP CONSTRAINT IF EXISTS {urls}_url_key;
+CREATE UNIQUE INDEX IF NOT EXISTS {urls}_namespace_id ON {urls} (namespace, id);
+CREATE UNIQUE INDEX IF NOT EXISTS {urls}_namespace_url ON {urls} (namespace, url);
+DO $$ BEGIN
+    IF NOT EXISTS (
+        SELECT 1 FROM pg_constraint
+        WHERE conname = '{clicks}_namespace_url_id_fkey'
+            AND connamespace = current_schema()::regnamespace
+    ) THEN
+        ALTER TABLE {clicks} ADD CONSTRAINT {clicks}_namespace_url_id_fkey
+            FOREIGN KEY (namespace, url_id) REFERENCES {urls} (namespace, id)
+            ON DELETE CASCADE ON UPDATE CASCADE;
+    END IF;
+END $$;
+
+ALTER TABLE {urls} ADD COLUMN IF NOT EXISTS tags TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE {urls} ADD COLUMN IF NOT EXISTS title TEXT;
+CREATE INDEX IF NOT EXISTS {urls}_namespace_lower_id ON {urls} (namespace, lower(id));
+ALTER TABLE {clicks} ALTER COLUMN url_id TYPE TEXT;
+ALTER TABLE {urls} ALTER COLUMN id TYPE TEXT;
+ALTER TABLE {urls} ADD COLUMN IF NOT EXISTS redirect_status SMALLINT NOT NULL DEFAULT 302;
+ALTER TABLE {urls} ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+DROP INDEX IF EXISTS {urls}_namespace_url;
+ALTER TABLE {urls} ADD COLUMN IF NOT EXISTS suffix_forward BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE {urls} ADD COLUMN IF NOT EXISTS created_ip INET;
+
+CREATE TABLE IF NOT EXISTS {audit_log} (
+    id BIGSERIAL PRIMARY KEY,
+    at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    actor TEXT,
+    action TEXT NOT NULL,
+    namespace TEXT NOT NULL DEFAULT '',
+    link_id TEXT
+);
+ALTER TABLE {urls} ADD COLUMN IF NOT EXISTS is_alias BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE {urls} ADD COLUMN IF NOT EXISTS enabled BOOLEAN NOT NULL DEFAULT true;
@@ -0,0 +1,4 @@
+-- Links that new shortens of their url may return. Cleared once a link stops
+-- resolving or a shorten asks for other options, freeing the url for a new
+-- link while the old one keeps its id.
+ALTER TABLE {urls} ADD COLUMN IF NOT EXISTS reusable BOOLEAN NOT NULL DEFAULT true;
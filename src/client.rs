@@ -0,0 +1,86 @@
+use reqwest::{
+    header::{AUTHORIZATION, CONTENT_TYPE, LOCATION},
+    redirect::Policy,
+    StatusCode,
+};
+use thiserror::Error;
+
+use crate::{ErrorBody, ShortnResponse};
+
+/// Typed HTTP client for the shortener's REST API.
+#[derive(Debug, Clone)]
+pub struct ShortenerClient {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("Request to the shortener failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("Unexpected response body: {0}")]
+    InvalidBody(#[from] serde_json::Error),
+    #[error("Shortener answered {status}: {message}")]
+    Api { status: StatusCode, message: String },
+}
+
+impl ShortenerClient {
+    /// `base_url` is the service root, e.g. `https://sho.rt`. The key is sent
+    /// as a bearer token when the service requires one.
+    pub fn new(base_url: &str, api_key: Option<String>) -> Result<Self, ClientError> {
+        let http = reqwest::Client::builder()
+            // `resolve` reads the redirect instead of following it.
+            .redirect(Policy::none())
+            .build()?;
+        Ok(Self {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+        })
+    }
+
+    pub async fn shorten(&self, url: &str) -> Result<ShortnResponse, ClientError> {
+        let mut request = self
+            .http
+            .post(format!("{}/v1/shorten", self.base_url))
+            .header(CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(&serde_json::json!({ "url": url }))?);
+        if let Some(key) = &self.api_key {
+            request = request.header(AUTHORIZATION, format!("Bearer {}", key));
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.bytes().await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body));
+        }
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// The url `id` redirects to, looked up with `HEAD` so it isn't counted
+    /// as a click.
+    pub async fn resolve(&self, id: &str) -> Result<String, ClientError> {
+        let response = self
+            .http
+            .head(format!("{}/{}", self.base_url, id))
+            .send()
+            .await?;
+        let status = response.status();
+        match response
+            .headers()
+            .get(LOCATION)
+            .and_then(|v| v.to_str().ok())
+        {
+            Some(location) if status.is_redirection() => Ok(location.to_string()),
+            _ => Err(api_error(status, &[])),
+        }
+    }
+}
+
+fn api_error(status: StatusCode, body: &[u8]) -> ClientError {
+    let message = serde_json::from_slice::<ErrorBody>(body)
+        .map(|e| e.error)
+        .unwrap_or_else(|_| status.canonical_reason().unwrap_or_default().to_string());
+    ClientError::Api { status, message }
+}
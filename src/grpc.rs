@@ -0,0 +1,98 @@
+use std::net::SocketAddr;
+
+use axum::http::StatusCode;
+use tonic::{transport::Server, Code, Request, Response, Status};
+use tracing::{error, info};
+
+use crate::{link_path, validate_namespace, AppState, LinkOptions, Shortened, ShortnError};
+
+mod pb {
+    tonic::include_proto!("shortener.v1");
+}
+
+use pb::shortener_server::{Shortener, ShortenerServer};
+
+/// gRPC front end delegating to the same `AppState` as the REST API.
+struct ShortenerService {
+    state: AppState,
+}
+
+/// Runs the gRPC server on `addr` until it fails.
+pub async fn serve(state: AppState, addr: SocketAddr) {
+    let service = ShortenerServer::new(ShortenerService { state });
+    if let Err(e) = Server::builder().add_service(service).serve(addr).await {
+        error!("gRPC server on {} failed: {}", addr, e);
+    }
+}
+
+#[tonic::async_trait]
+impl Shortener for ShortenerService {
+    async fn shorten(
+        &self,
+        request: Request<pb::ShortenRequest>,
+    ) -> Result<Response<pb::ShortenReply>, Status> {
+        let authorization = request
+            .metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok());
+        let caller = self
+            .state
+            .config
+            .authenticate(authorization)
+            .ok_or_else(|| Status::unauthenticated("missing or unknown API key"))?;
+        let created_ip = request.remote_addr().map(|addr| addr.ip());
+        let data = request.into_inner();
+
+        validate_namespace(&data.namespace)?;
+        let url = self.state.validate_and_normalize(&data.url).await?;
+        let options = LinkOptions {
+            namespace: data.namespace.clone(),
+            owner: caller.owner.clone(),
+            expires_at: self.state.config.default_expiry(),
+            expires_by_default: true,
+            created_ip,
+            quota: self.state.config.quota_for(&caller),
+            ..Default::default()
+        };
+        let Shortened { id, created } = self.state.shortn(&url, &options).await?;
+        if created {
+            self.state
+                .audit(&caller, "create", &data.namespace, Some(&id))
+                .await;
+        }
+        let short_url = format!(
+            "{}/{}",
+            self.state.config.base_url,
+            link_path(&data.namespace, &id)
+        );
+
+        info!("Shortened URL over gRPC: {} -> {}", url, short_url);
+
+        Ok(Response::new(pb::ShortenReply { id, short_url }))
+    }
+
+    async fn resolve(
+        &self,
+        request: Request<pb::ResolveRequest>,
+    ) -> Result<Response<pb::ResolveReply>, Status> {
+        let data = request.into_inner();
+        let target = self.state.peek_url(&data.namespace, &data.id).await?;
+
+        Ok(Response::new(pb::ResolveReply { url: target.url }))
+    }
+}
+
+impl From<ShortnError> for Status {
+    fn from(e: ShortnError) -> Self {
+        let code = match e.status() {
+            StatusCode::BAD_REQUEST => Code::InvalidArgument,
+            StatusCode::NOT_FOUND | StatusCode::GONE => Code::NotFound,
+            StatusCode::FORBIDDEN => Code::PermissionDenied,
+            StatusCode::CONFLICT => Code::AlreadyExists,
+            StatusCode::TOO_MANY_REQUESTS => Code::ResourceExhausted,
+            StatusCode::SERVICE_UNAVAILABLE => Code::Unavailable,
+            _ => Code::Internal,
+        };
+        Status::new(code, e.to_string())
+    }
+}
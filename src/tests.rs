@@ -0,0 +1,2810 @@
+//! Tests driving the router against the Postgres at `DATABASE_URL`, each in
+//! a schema of its own. They are skipped when `DATABASE_URL` is unset.
+
+use std::{env, net::SocketAddr, ops::Deref};
+
+use axum::{
+    body::Body,
+    http::{
+        header::{CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_NONE_MATCH},
+        Request as HttpRequest,
+    },
+    response::{Html, Response},
+};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_sdk::{testing::trace::InMemorySpanExporter, trace::TracerProvider};
+use serde_json::{json, Value};
+use sqlx::{Connection, PgConnection};
+use tower::ServiceExt;
+use tracing_subscriber::layer::SubscriberExt;
+
+use super::*;
+
+/// State on a fresh schema, dropped along with it.
+struct TestState {
+    state: AppState,
+    db_url: String,
+    schema: String,
+}
+
+impl Deref for TestState {
+    type Target = AppState;
+
+    fn deref(&self) -> &AppState {
+        &self.state
+    }
+}
+
+impl Drop for TestState {
+    fn drop(&mut self) {
+        let sql = format!("DROP SCHEMA {} CASCADE", self.schema);
+        let db_url = self.db_url.clone();
+        // Drop can't await, so the schema goes on a runtime of its own.
+        let _ = std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async {
+                let mut conn = PgConnection::connect(&db_url).await?;
+                sqlx::query(&sql).execute(&mut conn).await
+            })
+        })
+        .join();
+    }
+}
+
+/// State on a fresh schema, with `configure` applied over the environment's
+/// settings; `None` without a database to test against.
+async fn test_state(configure: impl FnOnce(&mut AppConfig)) -> Option<TestState> {
+    let Ok(db_url) = env::var("DATABASE_URL") else {
+        eprintln!("DATABASE_URL is unset, skipping");
+        return None;
+    };
+    let alphabet: Vec<char> = ('a'..='z').chain('0'..='9').collect();
+    let schema = format!("test_{}", nanoid!(12, &alphabet));
+    Some(schema_state(&db_url, &schema, configure).await)
+}
+
+/// State on `schema`, created in the database at `db_url` and searched
+/// ahead of `public`.
+async fn schema_state(
+    db_url: &str,
+    schema: &str,
+    configure: impl FnOnce(&mut AppConfig),
+) -> TestState {
+    let mut conn = PgConnection::connect(db_url).await.unwrap();
+    sqlx::query(&format!("CREATE SCHEMA {}", schema))
+        .execute(&mut conn)
+        .await
+        .unwrap();
+
+    let mut config = AppConfig::from_env();
+    config.db_url = schema_url(db_url, schema);
+    config.db_replica_url = None;
+    configure(&mut config);
+    let state = AppState::try_new(config).await.unwrap();
+    TestState {
+        state,
+        db_url: db_url.to_string(),
+        schema: schema.to_string(),
+    }
+}
+
+fn schema_url(db_url: &str, schema: &str) -> String {
+    let separator = if db_url.contains('?') { '&' } else { '?' };
+    format!(
+        "{}{}options=-c%20search_path%3D{}",
+        db_url, separator, schema
+    )
+}
+
+async fn send(state: &AppState, request: HttpRequest<Body>) -> Response {
+    build_router(state.clone()).oneshot(request).await.unwrap()
+}
+
+fn get(uri: &str) -> HttpRequest<Body> {
+    HttpRequest::get(uri).body(Body::empty()).unwrap()
+}
+
+fn post_json(uri: &str, body: Value) -> HttpRequest<Body> {
+    HttpRequest::post(uri)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn put_json(uri: &str, body: Value) -> HttpRequest<Body> {
+    HttpRequest::put(uri)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+async fn body_json(response: Response) -> Value {
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+async fn body_bytes(response: Response) -> axum::body::Bytes {
+    axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap()
+}
+
+fn location(response: &Response) -> &str {
+    response.headers()[LOCATION].to_str().unwrap()
+}
+
+/// Shortens through `POST /`, expecting a new link, and returns its id.
+async fn create(state: &AppState, body: Value) -> String {
+    let response = send(state, post_json("/", body)).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    body_json(response).await["id"]
+        .as_str()
+        .unwrap()
+        .to_string()
+}
+
+/// Serves `router` on a local port for the service to fetch from.
+async fn serve(router: Router) -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+    addr
+}
+
+fn with_key(mut request: HttpRequest<Body>, key: &str) -> HttpRequest<Body> {
+    let value = HeaderValue::from_str(&format!("Bearer {}", key)).unwrap();
+    request.headers_mut().insert(AUTHORIZATION, value);
+    request
+}
+
+/// Collects everything written to it, for reading logs back.
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Buffer {
+    fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl std::io::Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for Buffer {
+    type Writer = Self;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// Times the link with `id` has been used.
+async fn uses(state: &AppState, id: &str) -> i64 {
+    let (uses,): (i64,) = sqlx::query_as("SELECT uses FROM urls WHERE id = $1")
+        .bind(id)
+        .fetch_one(&state.db)
+        .await
+        .unwrap();
+    uses
+}
+
+#[tokio::test]
+async fn resolve_maps_unknown_ids_to_null() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let first = create(&state, json!({"url": "https://example.com/first"})).await;
+    let second = create(&state, json!({"url": "https://example.com/second"})).await;
+
+    let ids = json!({"ids": [first, "unknown", second]});
+    let response = send(&state, post_json("/v1/resolve", ids)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let urls = body_json(response).await;
+    assert_eq!(urls.as_object().unwrap().len(), 3);
+    assert_eq!(urls[&first], "https://example.com/first");
+    assert_eq!(urls["unknown"], Value::Null);
+    assert_eq!(urls[&second], "https://example.com/second");
+}
+
+#[tokio::test]
+async fn resolve_skips_used_up_links_and_ignores_case_like_redirects() {
+    let Some(state) = test_state(|config| config.case_insensitive_ids = true).await else {
+        return;
+    };
+    let once = create(
+        &state,
+        json!({"url": "https://example.com/once", "max_uses": 1}),
+    )
+    .await;
+    let kept = create(&state, json!({"url": "https://example.com/kept"})).await;
+    send(&state, get(&format!("/{}", once))).await;
+
+    let upper = kept.to_uppercase();
+    let ids = json!({"ids": [once, upper]});
+    let response = send(&state, post_json("/v1/resolve", ids)).await;
+    let urls = body_json(response).await;
+    assert_eq!(urls[&once], Value::Null);
+    assert_eq!(urls[&upper], "https://example.com/kept");
+}
+
+#[tokio::test]
+async fn resolve_refuses_more_ids_than_the_cap() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let ids: Vec<String> = (0..=MAX_RESOLVE_IDS).map(|i| i.to_string()).collect();
+
+    let response = send(&state, post_json("/v1/resolve", json!({ "ids": ids }))).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn link_stops_redirecting_past_max_uses() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let id = create(
+        &state,
+        json!({"url": "https://example.com/limited", "max_uses": 2}),
+    )
+    .await;
+
+    for _ in 0..2 {
+        let response = send(&state, get(&format!("/{}", id))).await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+    }
+    let response = send(&state, get(&format!("/{}", id))).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(uses(&state, &id).await, 2);
+}
+
+#[tokio::test]
+async fn head_answers_like_a_redirect_without_a_body() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let id = create(&state, json!({"url": "https://example.com/head"})).await;
+
+    let request = HttpRequest::head(format!("/{}", id))
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&state, request).await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(location(&response), "https://example.com/head");
+    assert!(body_bytes(response).await.is_empty());
+    assert_eq!(uses(&state, &id).await, 0);
+}
+
+#[tokio::test]
+async fn redirects_are_cached_by_link_kind() {
+    let Some(state) = test_state(|config| {
+        config.redirect_max_age = 86400;
+        config.redirect_temp_max_age = 0;
+    })
+    .await
+    else {
+        return;
+    };
+    let permanent = create(
+        &state,
+        json!({"url": "https://example.com/permanent", "status": 301}),
+    )
+    .await;
+    let expiring = create(
+        &state,
+        json!({"url": "https://example.com/expiring", "status": 301, "ttl_seconds": 120}),
+    )
+    .await;
+    let temporary = create(&state, json!({"url": "https://example.com/temporary"})).await;
+    let limited = create(
+        &state,
+        json!({"url": "https://example.com/limited", "status": 301, "max_uses": 5}),
+    )
+    .await;
+
+    let response = send(&state, get(&format!("/{}", permanent))).await;
+    assert_eq!(response.headers()[CACHE_CONTROL], "public, max-age=86400");
+    let response = send(&state, get(&format!("/{}", expiring))).await;
+    let cache_control = response.headers()[CACHE_CONTROL].to_str().unwrap();
+    let max_age: i64 = cache_control
+        .strip_prefix("public, max-age=")
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((1..=120).contains(&max_age), "{}", cache_control);
+    assert!(response.headers().contains_key(EXPIRES));
+    let response = send(&state, get(&format!("/{}", temporary))).await;
+    assert_eq!(response.headers()[CACHE_CONTROL], "no-cache");
+    let response = send(&state, get(&format!("/{}", limited))).await;
+    assert_eq!(response.headers()[CACHE_CONTROL], "no-store");
+}
+
+#[tokio::test]
+async fn matching_if_none_match_is_not_modified() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let id = create(&state, json!({"url": "https://example.com/etag"})).await;
+    let response = send(&state, get(&format!("/{}", id))).await;
+    let etag = response.headers()[ETAG].clone();
+
+    let request = HttpRequest::get(format!("/{}", id))
+        .header(IF_NONE_MATCH, etag)
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&state, request).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert!(!response.headers().contains_key(LOCATION));
+}
+
+#[tokio::test]
+async fn daily_stats_group_clicks_by_day() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let id = create(&state, json!({"url": "https://example.com/daily"})).await;
+    sqlx::query(
+        r#"
+        INSERT INTO clicks (url_id, visited_at)
+        VALUES ($1, now() - interval '1 day'), ($1, now() - interval '1 day'), ($1, now())
+        "#,
+    )
+    .bind(&id)
+    .execute(&state.db)
+    .await
+    .unwrap();
+
+    let response = send(&state, get(&format!("/v1/links/{}/stats/daily?days=2", id))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let counts: Vec<i64> = body_json(response)
+        .await
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|day| day["count"].as_i64().unwrap())
+        .collect();
+    assert_eq!(counts, [2, 1]);
+}
+
+#[tokio::test]
+async fn top_orders_links_by_clicks() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let mut ids = Vec::new();
+    for (path, clicks) in [("one", 1), ("three", 3), ("two", 2)] {
+        let url = format!("https://example.com/{}", path);
+        let id = create(&state, json!({ "url": url })).await;
+        for _ in 0..clicks {
+            send(&state, get(&format!("/{}", id))).await;
+        }
+        ids.push(id);
+    }
+
+    let response = send(&state, get("/v1/top?limit=3")).await;
+    let top: Vec<String> = body_json(response)
+        .await
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|link| link["id"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(top, [ids[1].clone(), ids[2].clone(), ids[0].clone()]);
+}
+
+#[tokio::test]
+async fn links_resolve_only_in_their_namespace() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let mut ids = Vec::new();
+    for ns in ["alpha", "beta"] {
+        let url = format!("https://example.com/{}", ns);
+        ids.push(create(&state, json!({"url": url, "namespace": ns})).await);
+    }
+
+    let response = send(&state, get(&format!("/alpha/{}", ids[0]))).await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(location(&response), "https://example.com/alpha");
+    for path in [
+        format!("/beta/{}", ids[0]),
+        format!("/{}", ids[0]),
+        format!("/{}", ids[1]),
+    ] {
+        let response = send(&state, get(&path)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn exhausted_pool_answers_503_with_retry_after() {
+    let Some(state) = test_state(|config| {
+        config.db_max_connections = 1;
+        config.db_acquire_timeout = StdDuration::from_millis(200);
+    })
+    .await
+    else {
+        return;
+    };
+    let held = state.db.acquire().await.unwrap();
+
+    let response = send(&state, get("/v1/top")).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(
+        response.headers()[RETRY_AFTER],
+        RETRY_AFTER_SECS.to_string()
+    );
+
+    drop(held);
+    let response = send(&state, get("/v1/top")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn slow_statements_are_cancelled_with_503() {
+    let Some(state) = test_state(|config| {
+        config.db_statement_timeout = Some(StdDuration::from_millis(500));
+    })
+    .await
+    else {
+        return;
+    };
+    // Startup migrates and counts the keyspace free of the timeout.
+    AppState::try_new(AppConfig {
+        db_url: state.config.db_url.clone(),
+        db_replica_url: None,
+        db_statement_timeout: Some(StdDuration::from_millis(1)),
+        ..AppConfig::from_env()
+    })
+    .await
+    .unwrap();
+    let id = create(&state, json!({"url": "https://example.com/locked"})).await;
+    let mut tx = state.db.begin().await.unwrap();
+    sqlx::query("LOCK TABLE urls IN ACCESS EXCLUSIVE MODE")
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+
+    let response = send(&state, get(&format!("/{}", id))).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(
+        response.headers()[RETRY_AFTER],
+        RETRY_AFTER_SECS.to_string()
+    );
+
+    tx.rollback().await.unwrap();
+    let response = send(&state, get(&format!("/{}", id))).await;
+    assert_eq!(location(&response), "https://example.com/locked");
+}
+
+#[tokio::test]
+async fn own_base_url_is_refused() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let url = format!("{}/abc123", state.config.base_url);
+
+    let response = send(&state, post_json("/", json!({ "url": url }))).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let error = body_json(response).await["error"].to_string();
+    assert!(error.contains("redirect loop"), "{}", error);
+}
+
+#[tokio::test]
+async fn links_are_filtered_by_tag() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let news = create(
+        &state,
+        json!({"url": "https://example.com/news", "tags": ["news"]}),
+    )
+    .await;
+    create(
+        &state,
+        json!({"url": "https://example.com/sport", "tags": ["sport"]}),
+    )
+    .await;
+    let both = create(
+        &state,
+        json!({"url": "https://example.com/both", "tags": ["news", "sport"]}),
+    )
+    .await;
+
+    let response = send(&state, get("/v1/links?tag=news")).await;
+    let mut ids: Vec<String> = body_json(response).await["links"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|link| link["id"].as_str().unwrap().to_string())
+        .collect();
+    ids.sort();
+    let mut expected = vec![news, both];
+    expected.sort();
+    assert_eq!(ids, expected);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrent_shortens_of_a_url_share_one_link() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let url = "https://example.com/concurrent";
+    let tasks: Vec<_> = (0..2)
+        .map(|_| {
+            let state = state.clone();
+            tokio::spawn(async move { state.shortn(url, &LinkOptions::default()).await })
+        })
+        .collect();
+    let mut results = Vec::new();
+    for task in tasks {
+        results.push(task.await.unwrap().unwrap());
+    }
+
+    assert_eq!(results[0].id, results[1].id);
+    assert_eq!(results.iter().filter(|r| r.created).count(), 1);
+    let (links,): (i64,) = sqlx::query_as("SELECT count(*) FROM urls")
+        .fetch_one(&state.db)
+        .await
+        .unwrap();
+    assert_eq!(links, 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn many_concurrent_shortens_return_one_stable_id() {
+    let Some(state) = test_state(|config| config.dedupe_urls = true).await else {
+        return;
+    };
+    let body = json!({"url": "https://example.com/stampede"});
+    let tasks: Vec<_> = (0..32)
+        .map(|_| {
+            let (state, body) = (state.clone(), body.clone());
+            tokio::spawn(async move {
+                let response = send(&state, post_json("/", body)).await;
+                let status = response.status();
+                (status, body_json(response).await["id"].clone())
+            })
+        })
+        .collect();
+    let mut ids = Vec::new();
+    let mut created = 0;
+    for task in tasks {
+        let (status, id) = task.await.unwrap();
+        assert!(status.is_success(), "{}", status);
+        created += usize::from(status == StatusCode::CREATED);
+        ids.push(id);
+    }
+
+    assert_eq!(created, 1);
+    assert!(ids.iter().all(|id| *id == ids[0]), "{:?}", ids);
+    let (links,): (i64,) = sqlx::query_as("SELECT count(*) FROM urls")
+        .fetch_one(&state.db)
+        .await
+        .unwrap();
+    assert_eq!(links, 1);
+    // No generated id was counted as a collision while waiting on the others.
+    assert_eq!(state.id_metrics.collisions.load(Ordering::Relaxed), 0);
+}
+
+#[tokio::test]
+async fn fetch_meta_stores_the_page_title() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let page = Router::new().route(
+        "/",
+        axum::routing::get(|| async {
+            Html("<html><head><title>Known &amp; Title</title></head></html>")
+        }),
+    );
+    let addr = serve(page).await;
+
+    let url = format!("http://{}/", addr);
+    let response = send(
+        &state,
+        post_json("/?fetch_meta=true", json!({ "url": url })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let id = body_json(response).await["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let title = state.link_stats("", &id).await.unwrap().title;
+    assert_eq!(title.as_deref(), Some("Known & Title"));
+}
+
+#[tokio::test]
+async fn title_redirects_are_followed_only_to_storable_urls() {
+    let Some(open) = test_state(|config| config.block_private_hosts = false).await else {
+        return;
+    };
+    let Some(blocking) = test_state(|config| config.block_private_hosts = true).await else {
+        return;
+    };
+    let page = Router::new()
+        .route(
+            "/moved",
+            axum::routing::get(|| async { axum::response::Redirect::temporary("/internal") }),
+        )
+        .route(
+            "/internal",
+            axum::routing::get(|| async { Html("<title>Internal</title>") }),
+        );
+    let addr = serve(page).await;
+    let url = format!("http://{}/moved", addr);
+
+    assert_eq!(open.fetch_title(&url).await.as_deref(), Some("Internal"));
+    // The redirect leads to a loopback address, which isn't fetched.
+    assert_eq!(blocking.fetch_title(&url).await, None);
+}
+
+#[tokio::test]
+async fn unicode_host_round_trips_as_punycode() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let id = create(&state, json!({"url": "https://bücher.example/straße"})).await;
+    let stored = "https://xn--bcher-kva.example/stra%C3%9Fe";
+    assert_eq!(state.link_stats("", &id).await.unwrap().url, stored);
+
+    let response = send(&state, get(&format!("/{}", id))).await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(location(&response), stored);
+}
+
+#[tokio::test]
+async fn url_with_a_newline_is_refused_at_creation() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    for url in [
+        "https://example.com/a\nb",
+        "https://example.com/\r\nSet-Cookie: a=b",
+    ] {
+        let response = send(&state, post_json("/", json!({ "url": url }))).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+    let response = send(&state, get("/v1/stats/summary")).await;
+    assert_eq!(body_json(response).await["total_links"], 0);
+}
+
+#[tokio::test]
+async fn v1_shorten_links_resolve_at_the_root() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let response = send(
+        &state,
+        post_json("/v1/shorten", json!({"url": "https://example.com/v1"})),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let id = body_json(response).await["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let response = send(&state, get(&format!("/{}", id))).await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(location(&response), "https://example.com/v1");
+}
+
+#[tokio::test]
+async fn private_destinations_are_refused() {
+    let Some(state) = test_state(|config| config.block_private_hosts = true).await else {
+        return;
+    };
+    for url in ["http://10.0.0.5/", "http://localhost/", "http://[::1]/"] {
+        let response = send(&state, post_json("/", json!({ "url": url }))).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", url);
+    }
+    create(&state, json!({"url": "http://1.1.1.1/"})).await;
+}
+
+#[tokio::test]
+async fn ids_are_case_sensitive_by_default() {
+    let Some(state) = test_state(|config| config.case_insensitive_ids = false).await else {
+        return;
+    };
+    sqlx::query("INSERT INTO urls (id, url) VALUES ('MixedC', 'https://example.com/case')")
+        .execute(&state.db)
+        .await
+        .unwrap();
+
+    let response = send(&state, get("/MixedC")).await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+    let response = send(&state, get("/mixedc")).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn case_insensitive_ids_match_any_case() {
+    let Some(state) = test_state(|config| config.case_insensitive_ids = true).await else {
+        return;
+    };
+    sqlx::query("INSERT INTO urls (id, url) VALUES ('MixedC', 'https://example.com/stored')")
+        .execute(&state.db)
+        .await
+        .unwrap();
+    let id = create(&state, json!({"url": "https://example.com/generated"})).await;
+    assert_eq!(id, id.to_lowercase());
+
+    for path in ["/mixedc", "/MIXEDC"] {
+        let response = send(&state, get(path)).await;
+        assert_eq!(response.status(), StatusCode::FOUND, "{}", path);
+        assert_eq!(location(&response), "https://example.com/stored");
+    }
+    let response = send(&state, get(&format!("/{}", id.to_uppercase()))).await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(location(&response), "https://example.com/generated");
+}
+
+/// Needs `DATABASE_REPLICA_URL` naming a second database, which stands in
+/// for a replica holding a different row under the same id.
+#[tokio::test]
+async fn reads_go_to_the_replica_and_redirects_to_the_primary() {
+    let Ok(replica_url) = env::var("DATABASE_REPLICA_URL") else {
+        eprintln!("DATABASE_REPLICA_URL is unset, skipping");
+        return;
+    };
+    let Some(primary) = test_state(|_| {}).await else {
+        return;
+    };
+    let replica = schema_state(&replica_url, &primary.schema, |_| {}).await;
+    for (state, url) in [
+        (&primary, "https://example.com/primary"),
+        (&replica, "https://example.com/replica"),
+    ] {
+        sqlx::query("INSERT INTO urls (id, url) VALUES ('mirror', $1)")
+            .bind(url)
+            .execute(&state.db)
+            .await
+            .unwrap();
+    }
+
+    let state = AppState::try_new(AppConfig {
+        db_url: primary.config.db_url.clone(),
+        db_replica_url: Some(replica.config.db_url.clone()),
+        ..AppConfig::from_env()
+    })
+    .await
+    .unwrap();
+    let response = send(&state, get("/v1/links/mirror/stats")).await;
+    assert_eq!(
+        body_json(response).await["url"],
+        "https://example.com/replica"
+    );
+    let request = HttpRequest::head("/mirror").body(Body::empty()).unwrap();
+    let response = send(&state, request).await;
+    assert_eq!(location(&response), "https://example.com/replica");
+    // Redirects count the use, so they stay on the primary.
+    let response = send(&state, get("/mirror")).await;
+    assert_eq!(location(&response), "https://example.com/primary");
+}
+
+#[tokio::test]
+async fn openapi_documents_shorten_with_201() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let response = send(&state, get("/api-docs/openapi.json")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let doc = body_json(response).await;
+    assert!(doc["paths"]["/"]["post"]["responses"]["201"].is_object());
+}
+
+#[tokio::test]
+async fn unchanged_stats_are_not_modified() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let id = create(&state, json!({"url": "https://example.com/stats-etag"})).await;
+    let uri = format!("/v1/links/{}/stats", id);
+    let response = send(&state, get(&uri)).await;
+    let etag = response.headers()[ETAG].clone();
+    assert!(etag.to_str().unwrap().starts_with("W/"));
+
+    let request = HttpRequest::get(&uri)
+        .header(IF_NONE_MATCH, etag.clone())
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&state, request).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    send(&state, get(&format!("/{}", id))).await;
+    let request = HttpRequest::get(&uri)
+        .header(IF_NONE_MATCH, etag)
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&state, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn favicon_is_answered_without_the_database() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    state.close().await;
+
+    let response = send(&state, get("/favicon.ico")).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(response.headers().contains_key(CACHE_CONTROL));
+}
+
+#[tokio::test]
+async fn created_link_is_in_location_and_body() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let response = send(
+        &state,
+        post_json("/", json!({"url": "https://example.com/location"})),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let location = location(&response).to_string();
+    let short_id = response.headers()[SHORT_ID_HEADER].clone();
+    let body = body_json(response).await;
+    assert_eq!(body["id"], short_id.to_str().unwrap());
+    assert_eq!(body["short_url"], location.as_str());
+    assert_eq!(body["original_url"], "https://example.com/location");
+    assert!(location.ends_with(&format!("/{}", body["id"].as_str().unwrap())));
+}
+
+#[tokio::test]
+async fn plus_suffix_shows_the_link_instead_of_redirecting() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let id = create(&state, json!({"url": "https://example.com/plus"})).await;
+
+    let response = send(&state, get(&format!("/{}+", id))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let stats = body_json(response).await;
+    assert_eq!(stats["url"], "https://example.com/plus");
+    assert_eq!(stats["clicks"], 0);
+
+    let response = send(&state, get(&format!("/{}", id))).await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+}
+
+#[tokio::test]
+async fn dry_run_validates_without_storing() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let response = send(
+        &state,
+        post_json(
+            "/v1/shorten?dry_run=true",
+            json!({"url": "https://example.com/dry"}),
+        ),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key(LOCATION));
+    let id = body_json(response).await["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let response = send(&state, get(&format!("/{}", id))).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = send(
+        &state,
+        post_json("/", json!({"url": "ftp://example.com", "dry_run": true})),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn shortn_span_is_exported_with_its_id_and_rows() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let exporter = InMemorySpanExporter::default();
+    let provider = TracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+    let guard = tracing::subscriber::set_default(subscriber);
+    let link = state
+        .shortn("https://example.com/traced", &LinkOptions::default())
+        .await
+        .unwrap();
+    drop(guard);
+    // Spans are handed to the exporter on a thread of its own.
+    provider.force_flush();
+
+    let spans = exporter.get_finished_spans().unwrap();
+    let span = spans.iter().find(|span| span.name == "shortn").unwrap();
+    let attribute = |key: &str| {
+        span.attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| kv.value.as_str().into_owned())
+    };
+    assert_eq!(attribute("id"), Some(link.id));
+    assert_eq!(attribute("db.rows_affected").as_deref(), Some("1"));
+    assert_eq!(attribute("db.system").as_deref(), Some("postgresql"));
+}
+
+#[tokio::test]
+async fn ids_failing_the_checksum_are_not_looked_up() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let id = create(&state, json!({"url": "https://example.com/checked"})).await;
+    let response = send(&state, get(&format!("/{}", id))).await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+
+    // Changing the check character makes the id fail its checksum, so
+    // even a stored row under it is never reached.
+    let (body, check) = id.split_at(id.len() - 1);
+    let typo = format!("{}{}", body, if check == "a" { "b" } else { "a" });
+    sqlx::query("INSERT INTO urls (id, url) VALUES ($1, 'https://example.com/typo')")
+        .bind(&typo)
+        .execute(&state.db)
+        .await
+        .unwrap();
+    let response = send(&state, get(&format!("/{}", typo))).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Ids that could be neither a generated id nor an alias are refused
+    // before any query, so a database that can't be reached never shows.
+    let unreachable = PgPoolOptions::new()
+        .acquire_timeout(StdDuration::from_millis(100))
+        .connect_lazy("postgres://127.0.0.1:1/unreachable")
+        .unwrap();
+    let offline = AppState {
+        db: unreachable.clone(),
+        replica: unreachable,
+        ..state.state.clone()
+    };
+    for id in [format!("{}.", body), "a".repeat(MAX_ID_LEN + 1)] {
+        let response = send(&offline, get(&format!("/{}", id))).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", id);
+        let response = send(&offline, get(&format!("/{}/qr", id))).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", id);
+    }
+    let response = send(&offline, get(&format!("/{}", typo))).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn cursor_pages_cover_every_link_once() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let mut created = Vec::new();
+    for i in 0..7 {
+        let url = format!("https://example.com/page/{}", i);
+        created.push(create(&state, json!({ "url": url })).await);
+    }
+    // Ties on created_at are where the cursor has to fall back on the id.
+    sqlx::query(
+        "UPDATE urls SET created_at = CASE WHEN id = ANY($1) \
+         THEN '2024-01-02T00:00:00Z'::timestamptz ELSE '2024-01-01T00:00:00Z'::timestamptz END",
+    )
+    .bind(&created[..4])
+    .execute(&state.db)
+    .await
+    .unwrap();
+
+    let mut seen = Vec::new();
+    let mut pages = 0;
+    let mut uri = "/v1/links?limit=2".to_string();
+    loop {
+        let page = body_json(send(&state, get(&uri)).await).await;
+        pages += 1;
+        let links = page["links"].as_array().unwrap();
+        assert!(links.len() <= 2);
+        seen.extend(links.iter().map(|l| l["id"].as_str().unwrap().to_string()));
+        match page["next_cursor"].as_str() {
+            Some(cursor) => uri = format!("/v1/links?limit=2&after={}", cursor),
+            None => break,
+        }
+    }
+    assert!(pages >= 3, "{} pages", pages);
+    let mut newest = seen[..4].to_vec();
+    newest.sort();
+    let mut expected = created[..4].to_vec();
+    expected.sort();
+    assert_eq!(newest, expected);
+    let mut unique = seen.clone();
+    unique.sort();
+    unique.dedup();
+    assert_eq!(unique.len(), seen.len(), "duplicates in {:?}", seen);
+    created.sort();
+    assert_eq!(unique, created);
+}
+
+#[tokio::test]
+async fn redirect_status_is_taken_from_the_request() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let id = create(
+        &state,
+        json!({"url": "https://example.com/moved", "status": 308}),
+    )
+    .await;
+    let response = send(&state, get(&format!("/{}", id))).await;
+    assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+
+    let response = send(
+        &state,
+        post_json(
+            "/",
+            json!({"url": "https://example.com/other", "status": 303}),
+        ),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn deleted_links_are_listed_for_admins_only() {
+    let Some(state) = test_state(|config| {
+        config.api_keys = vec![ApiKey::parse("root:kr"), ApiKey::parse("bob:kb")];
+        config.admin_owners = vec!["root".to_string()];
+    })
+    .await
+    else {
+        return;
+    };
+    let request = post_json("/v1/shorten", json!({"url": "https://example.com/gone"}));
+    let response = send(&state, with_key(request, "kr")).await;
+    let id = body_json(response).await["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let request = HttpRequest::delete(format!("/v1/links/{}", id))
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&state, with_key(request, "kr")).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = send(&state, get(&format!("/{}", id))).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = send(&state, with_key(get("/v1/links"), "kr")).await;
+    assert_eq!(body_json(response).await["links"], json!([]));
+    let request = get("/v1/links?include_deleted=true");
+    let response = send(&state, with_key(request, "kb")).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let request = get("/v1/links?include_deleted=true");
+    let response = send(&state, with_key(request, "kr")).await;
+    let links = body_json(response).await["links"].clone();
+    assert_eq!(links[0]["id"], id.as_str());
+    assert!(links[0]["deleted_at"].is_string());
+}
+
+#[tokio::test]
+async fn debug_shows_admins_what_stats_hide() {
+    let Some(state) = test_state(|config| {
+        config.api_keys = vec![ApiKey::parse("root:kr"), ApiKey::parse("bob:kb")];
+        config.admin_owners = vec!["root".to_string()];
+    })
+    .await
+    else {
+        return;
+    };
+    let mut request = post_json("/v1/shorten", json!({"url": "https://example.com/traced"}));
+    let peer: SocketAddr = "203.0.113.7:40000".parse().unwrap();
+    request.extensions_mut().insert(ConnectInfo(peer));
+    let response = send(&state, with_key(request, "kb")).await;
+    let id = body_json(response).await["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let path = format!("/{}/debug", id);
+
+    let response = send(&state, get(&path)).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = send(&state, with_key(get(&path), "kb")).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = send(&state, with_key(get(&path), "kr")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let record = body_json(response).await;
+    assert_eq!(record["id"], id.as_str());
+    assert_eq!(record["url"], "https://example.com/traced");
+    assert_eq!(record["owner"], "bob");
+    assert_eq!(record["created_ip"], "203.0.113.7");
+    assert_eq!(record["enabled"], true);
+    assert!(record["deleted_at"].is_null());
+
+    let response = send(&state, get(&format!("/v1/links/{}/stats", id))).await;
+    let stats = body_json(response).await;
+    assert_eq!(stats["id"], id.as_str());
+    for hidden in ["owner", "created_ip"] {
+        assert!(stats.get(hidden).is_none(), "{} in {}", hidden, stats);
+    }
+    let response = send(&state, with_key(get("/nope12/debug"), "kr")).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn export_is_for_admins_only() {
+    let Some(state) = test_state(|config| {
+        config.api_keys = vec![ApiKey::parse("root:kr"), ApiKey::parse("bob:kb")];
+        config.admin_owners = vec!["root".to_string()];
+    })
+    .await
+    else {
+        return;
+    };
+    let request = post_json(
+        "/v1/shorten",
+        json!({"url": "https://example.com/exported"}),
+    );
+    send(&state, with_key(request, "kb")).await;
+
+    let response = send(&state, with_key(get("/v1/export?format=csv"), "kb")).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = send(&state, with_key(get("/v1/export?format=csv"), "kr")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = String::from_utf8(body_bytes(response).await.to_vec()).unwrap();
+    assert!(body.contains("https://example.com/exported"), "{}", body);
+}
+
+#[tokio::test]
+async fn admin_routes_are_closed_without_api_keys() {
+    let Some(state) = test_state(|config| {
+        config.api_keys.clear();
+        config.admin_owners.clear();
+    })
+    .await
+    else {
+        return;
+    };
+    let id = create(&state, json!({"url": "https://example.com/anonymous"})).await;
+    let requests = [
+        get(&format!("/v1/admin/links/{}", id)),
+        get(&format!("/{}/debug", id)),
+        get("/v1/links?include_deleted=true"),
+        get("/v1/export"),
+        HttpRequest::post("/v1/admin/cleanup")
+            .body(Body::empty())
+            .unwrap(),
+        HttpRequest::delete("/v1/admin/all")
+            .header(CONFIRM_WIPE_HEADER, CONFIRM_WIPE_VALUE)
+            .body(Body::empty())
+            .unwrap(),
+    ];
+    for request in requests {
+        let uri = request.uri().clone();
+        let response = send(&state, request).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", uri);
+    }
+    let response = send(&state, get(&format!("/{}", id))).await;
+    assert_eq!(location(&response), "https://example.com/anonymous");
+}
+
+#[tokio::test]
+async fn summary_counts_links_and_clicks() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let id = create(&state, json!({"url": "https://example.com/counted"})).await;
+    create(&state, json!({"url": "https://example.com/unclicked"})).await;
+    for _ in 0..3 {
+        send(&state, get(&format!("/{}", id))).await;
+    }
+
+    let response = send(&state, get("/v1/stats/summary")).await;
+    let summary = body_json(response).await;
+    assert_eq!(summary["total_links"], 2);
+    assert_eq!(summary["total_clicks"], 3);
+    assert_eq!(summary["created_last_24h"], 2);
+}
+
+#[tokio::test]
+async fn dedupe_returns_the_existing_link() {
+    let Some(state) = test_state(|config| config.dedupe_urls = true).await else {
+        return;
+    };
+    let body = json!({"url": "https://example.com/twice"});
+    let first = create(&state, body.clone()).await;
+    let response = send(&state, post_json("/", body)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_json(response).await["id"], first.as_str());
+}
+
+#[tokio::test]
+async fn dedupe_skips_used_up_links() {
+    let Some(state) = test_state(|config| config.dedupe_urls = true).await else {
+        return;
+    };
+    let body = json!({"url": "https://example.com/once", "max_uses": 1});
+    let first = create(&state, body.clone()).await;
+    send(&state, get(&format!("/{}", first))).await;
+
+    let second = create(&state, body.clone()).await;
+    assert_ne!(first, second);
+    let response = send(&state, get(&format!("/{}", second))).await;
+    assert_eq!(location(&response), "https://example.com/once");
+    let response = send(&state, get(&format!("/{}", first))).await;
+    assert!(response.status().is_client_error(), "{}", response.status());
+}
+
+#[tokio::test]
+async fn dedupe_reuses_only_links_with_the_same_options() {
+    let Some(state) = test_state(|config| {
+        config.dedupe_urls = true;
+        config.default_ttl = Some(StdDuration::from_secs(3600));
+    })
+    .await
+    else {
+        return;
+    };
+    let url = "https://example.com/options";
+    let plain = create(&state, json!({ "url": url })).await;
+    let moved = create(&state, json!({"url": url, "status": 301})).await;
+    assert_ne!(plain, moved);
+    let response = send(&state, post_json("/", json!({"url": url, "status": 301}))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_json(response).await["id"], moved.as_str());
+
+    let tagged = create(&state, json!({"url": url, "tags": ["a"]})).await;
+    let permanent = create(&state, json!({"url": url, "permanent": true})).await;
+    let ids = [&plain, &moved, &tagged, &permanent];
+    assert!(ids
+        .iter()
+        .all(|id| ids.iter().filter(|other| other == &id).count() == 1));
+    // Without an expiry of its own, a request takes the link as it expires.
+    let response = send(&state, post_json("/", json!({ "url": url }))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_json(response).await["id"], permanent.as_str());
+}
+
+#[tokio::test]
+async fn dedupe_keeps_each_owner_to_their_own_links() {
+    let Some(state) = test_state(|config| {
+        config.api_keys = vec![ApiKey::parse("alice:ka"), ApiKey::parse("bob:kb")];
+        config.admin_owners.clear();
+        config.dedupe_urls = true;
+    })
+    .await
+    else {
+        return;
+    };
+    let body = json!({"url": "https://example.com/shared"});
+    let response = send(&state, with_key(post_json("/", body.clone()), "kb")).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let bob = body_json(response).await["id"].clone();
+
+    let response = send(&state, with_key(post_json("/", body.clone()), "ka")).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let alice = body_json(response).await["id"].clone();
+    assert_ne!(alice, bob);
+    let response = send(&state, with_key(post_json("/", body), "ka")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_json(response).await["id"], alice);
+
+    let response = send(&state, with_key(get("/v1/links"), "ka")).await;
+    let links = body_json(response).await["links"].clone();
+    assert_eq!(links.as_array().unwrap().len(), 1);
+    assert_eq!(links[0]["id"], alice);
+}
+
+#[tokio::test]
+async fn without_dedupe_every_shorten_is_a_new_link() {
+    let Some(state) = test_state(|config| config.dedupe_urls = false).await else {
+        return;
+    };
+    let body = json!({"url": "https://example.com/twice"});
+    let first = create(&state, body.clone()).await;
+    let second = create(&state, body).await;
+    assert_ne!(first, second);
+}
+
+#[test]
+fn trusted_proxy_ranges_match_by_prefix() {
+    let net = IpNet::parse("10.0.0.0/8").unwrap();
+    assert!(net.contains("10.1.2.3".parse().unwrap()));
+    assert!(net.contains("::ffff:10.1.2.3".parse().unwrap()));
+    assert!(!net.contains("11.0.0.1".parse().unwrap()));
+    let host = IpNet::parse("::1").unwrap();
+    assert!(host.contains("::1".parse().unwrap()));
+    assert!(!host.contains("::2".parse().unwrap()));
+    assert!(IpNet::parse("10.0.0.0/33").is_none());
+}
+
+#[tokio::test]
+async fn bad_json_bodies_are_400_with_a_reason() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    for body in ["", "not json", r#"{"max_uses": 2}"#] {
+        let request = HttpRequest::post("/")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap();
+        let response = send(&state, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{:?}", body);
+        let error = body_json(response).await["error"].to_string();
+        assert!(error.starts_with("\"Invalid JSON body"), "{}", error);
+    }
+}
+
+#[tokio::test]
+async fn serves_https_with_a_self_signed_cert() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let dir = env!("CARGO_MANIFEST_DIR");
+    let rustls = axum_server::tls_rustls::RustlsConfig::from_pem_file(
+        format!("{}/testdata/localhost-cert.pem", dir),
+        format!("{}/testdata/localhost-key.pem", dir),
+    )
+    .await
+    .unwrap();
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let service = build_router(state.clone()).into_make_service_with_connect_info::<SocketAddr>();
+    tokio::spawn(axum_server::from_tcp_rustls(listener, rustls).serve(service));
+
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+    let response = client
+        .get(format!("https://localhost:{}/favicon.ico", port))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn cleanup_removes_only_expired_links() {
+    let Some(state) = test_state(|config| {
+        config.api_keys = vec![ApiKey::parse("root:kr")];
+        config.admin_owners = vec!["root".to_string()];
+    })
+    .await
+    else {
+        return;
+    };
+    for path in ["kept", "expired"] {
+        let url = format!("https://example.com/{}", path);
+        let request = post_json("/", json!({ "url": url }));
+        send(&state, with_key(request, "kr")).await;
+    }
+    sqlx::query(
+        "UPDATE urls SET expires_at = now() - interval '1 second' WHERE url LIKE '%/expired'",
+    )
+    .execute(&state.db)
+    .await
+    .unwrap();
+
+    let request = HttpRequest::post("/v1/admin/cleanup")
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&state, with_key(request, "kr")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_json(response).await["removed"], 1);
+}
+
+#[tokio::test]
+async fn wipe_requires_the_confirmation_header() {
+    let Some(state) = test_state(|config| {
+        config.api_keys = vec![ApiKey::parse("root:kr")];
+        config.admin_owners = vec!["root".to_string()];
+    })
+    .await
+    else {
+        return;
+    };
+    let request = post_json("/", json!({"url": "https://example.com/wiped"}));
+    send(&state, with_key(request, "kr")).await;
+
+    let request = HttpRequest::delete("/v1/admin/all")
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&state, with_key(request, "kr")).await;
+    assert_eq!(response.status(), StatusCode::PRECONDITION_REQUIRED);
+
+    let request = HttpRequest::delete("/v1/admin/all")
+        .header(CONFIRM_WIPE_HEADER, CONFIRM_WIPE_VALUE)
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&state, with_key(request, "kr")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_json(response).await["removed"], 1);
+}
+
+#[tokio::test]
+async fn update_to_a_private_host_is_refused() {
+    let Some(state) = test_state(|config| config.block_private_hosts = true).await else {
+        return;
+    };
+    let id = create(&state, json!({"url": "https://93.184.216.34/start"})).await;
+
+    let request = HttpRequest::put(format!("/v1/links/{}", id))
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({"url": "http://127.0.0.1/"}).to_string()))
+        .unwrap();
+    let response = send(&state, request).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let stats = state.link_stats("", &id).await.unwrap();
+    assert_eq!(stats.url, "https://93.184.216.34/start");
+}
+
+#[tokio::test]
+async fn router_shortens_and_redirects_in_process() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let router = build_router(state.clone());
+
+    let request = post_json(
+        "/v1/shorten",
+        json!({"url": "https://example.com/embedded"}),
+    );
+    let response = router.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let id = body_json(response).await["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let response = router.oneshot(get(&format!("/{}", id))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(location(&response), "https://example.com/embedded");
+}
+
+#[tokio::test]
+async fn metrics_report_pool_gauges() {
+    let Some(state) = test_state(|config| config.db_max_connections = 4).await else {
+        return;
+    };
+    let response = send(&state, get("/metrics")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = String::from_utf8(body_bytes(response).await.to_vec()).unwrap();
+    assert!(
+        body.contains("# TYPE shortener_db_pool_size gauge"),
+        "{}",
+        body
+    );
+    assert!(
+        body.contains("shortener_db_pool_max{pool=\"primary\"} 4"),
+        "{}",
+        body
+    );
+    assert!(!body.contains("pool=\"replica\""), "{}", body);
+}
+
+#[tokio::test]
+async fn hash_ids_are_stable_per_url() {
+    let Some(state) = test_state(|config| {
+        config.id_strategy = IdStrategy::Hash;
+        config.dedupe_urls = false;
+    })
+    .await
+    else {
+        return;
+    };
+    let url = "https://example.com/hashed";
+    let first = state.shortn(url, &LinkOptions::default()).await.unwrap();
+    let second = state.shortn(url, &LinkOptions::default()).await.unwrap();
+    assert!(first.created);
+    assert!(!second.created);
+    assert_eq!(first.id, second.id);
+    let other = state
+        .shortn("https://example.com/other", &LinkOptions::default())
+        .await
+        .unwrap();
+    assert_ne!(other.id, first.id);
+}
+
+#[tokio::test]
+async fn hash_ids_do_not_depend_on_stored_links() {
+    let hashed = |config: &mut AppConfig| config.id_strategy = IdStrategy::Hash;
+    let (Some(first), Some(second)) = (test_state(hashed).await, test_state(hashed).await) else {
+        return;
+    };
+    let url = "https://example.com/restarted";
+    first
+        .shortn("https://example.com/earlier", &LinkOptions::default())
+        .await
+        .unwrap();
+    let before = first.shortn(url, &LinkOptions::default()).await.unwrap();
+    let after = second.shortn(url, &LinkOptions::default()).await.unwrap();
+    assert!(after.created);
+    assert_eq!(before.id, after.id);
+}
+
+#[tokio::test]
+async fn hash_collision_lengthens_the_id() {
+    let Some(state) = test_state(|config| config.id_strategy = IdStrategy::Hash).await else {
+        return;
+    };
+    let url = "https://example.com/collided";
+    let id_len = state.config.id_length;
+    let hashed = state.generate_id("", url, id_len, 0);
+    sqlx::query("INSERT INTO urls (id, url) VALUES ($1, 'https://example.com/squatter')")
+        .bind(&hashed)
+        .execute(&state.db)
+        .await
+        .unwrap();
+
+    let first = state.shortn(url, &LinkOptions::default()).await.unwrap();
+    assert!(first.created);
+    assert_eq!(first.id.len(), id_len + 1);
+    // The longer hash extends the shorter one; only the check character moves.
+    assert!(first.id.starts_with(&hashed[..id_len - 1]));
+    let again = state.shortn(url, &LinkOptions::default()).await.unwrap();
+    assert!(!again.created);
+    assert_eq!(again.id, first.id);
+}
+
+#[tokio::test]
+async fn random_ids_differ_per_shorten() {
+    let Some(state) = test_state(|config| {
+        config.id_strategy = IdStrategy::Random;
+        config.dedupe_urls = false;
+    })
+    .await
+    else {
+        return;
+    };
+    let url = "https://example.com/random";
+    let first = state.shortn(url, &LinkOptions::default()).await.unwrap();
+    let second = state.shortn(url, &LinkOptions::default()).await.unwrap();
+    assert_ne!(first.id, second.id);
+}
+
+#[tokio::test]
+async fn quota_limits_each_key_on_its_own() {
+    let Some(state) = test_state(|config| {
+        config.api_keys = vec![ApiKey::parse("alice:ka"), ApiKey::parse("bob:kb")];
+        config.admin_owners.clear();
+        config.link_quota = Some(1);
+    })
+    .await
+    else {
+        return;
+    };
+    let shorten = |key: &'static str, path: &'static str| {
+        let state = state.clone();
+        async move {
+            let url = format!("https://example.com/{}", path);
+            send(&state, with_key(post_json("/", json!({ "url": url })), key)).await
+        }
+    };
+
+    let response = shorten("ka", "first").await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let alice = body_json(response).await["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let response = shorten("ka", "second").await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let response = shorten("kb", "third").await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let bob = body_json(response).await["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert_ne!(alice, bob);
+
+    let response = send(&state, with_key(get("/v1/links"), "kb")).await;
+    let links = body_json(response).await["links"].clone();
+    assert_eq!(links.as_array().unwrap().len(), 1);
+    assert_eq!(links[0]["id"], bob.as_str());
+    let request = HttpRequest::delete(format!("/v1/links/{}", alice))
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&state, with_key(request, "kb")).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrent_shortens_stay_within_the_quota() {
+    let Some(state) = test_state(|config| {
+        config.api_keys = vec![ApiKey::parse("alice:ka")];
+        config.admin_owners.clear();
+        config.link_quota = Some(2);
+    })
+    .await
+    else {
+        return;
+    };
+    let tasks: Vec<_> = (0..8)
+        .map(|i| {
+            let state = state.clone();
+            tokio::spawn(async move {
+                let url = format!("https://example.com/race/{}", i);
+                let request = with_key(post_json("/", json!({ "url": url })), "ka");
+                send(&state, request).await.status()
+            })
+        })
+        .collect();
+    let mut created = 0;
+    for task in tasks {
+        let status = task.await.unwrap();
+        assert!(
+            status == StatusCode::CREATED || status == StatusCode::TOO_MANY_REQUESTS,
+            "{}",
+            status
+        );
+        created += usize::from(status == StatusCode::CREATED);
+    }
+
+    assert_eq!(created, 2);
+}
+
+#[tokio::test]
+async fn imports_count_toward_the_quota() {
+    let Some(state) = test_state(|config| {
+        config.api_keys = vec![ApiKey::parse("alice:ka")];
+        config.admin_owners.clear();
+        config.link_quota = Some(2);
+    })
+    .await
+    else {
+        return;
+    };
+    let request = with_key(
+        post_json("/", json!({"url": "https://example.com/live"})),
+        "ka",
+    );
+    assert_eq!(send(&state, request).await.status(), StatusCode::CREATED);
+
+    let rows = json!([
+        {"id": "imp-1", "url": "https://example.com/1"},
+        {"id": "imp-2", "url": "https://example.com/2"},
+    ]);
+    let response = send(&state, with_key(post_json("/v1/import", rows), "ka")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let response = send(&state, get("/imp-1")).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let rows = json!([{"id": "imp-1", "url": "https://example.com/1"}]);
+    let response = send(&state, with_key(post_json("/v1/import", rows), "ka")).await;
+    assert_eq!(body_json(response).await["inserted"], 1);
+    let request = with_key(
+        post_json("/", json!({"url": "https://example.com/more"})),
+        "ka",
+    );
+    assert_eq!(
+        send(&state, request).await.status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+}
+
+#[tokio::test]
+async fn links_expire_at_the_requested_date() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let expires_at = (Utc::now() + Duration::days(7)).to_rfc3339();
+    let id = create(
+        &state,
+        json!({"url": "https://example.com/dated", "expires_at": expires_at}),
+    )
+    .await;
+    let stats = state.link_stats("", &id).await.unwrap();
+    let stored = stats.expires_at.unwrap();
+    let requested = DateTime::parse_from_rfc3339(&expires_at).unwrap();
+    assert!(
+        (stored - requested.with_timezone(&Utc))
+            .num_milliseconds()
+            .abs()
+            < 1
+    );
+
+    sqlx::query("UPDATE urls SET expires_at = now() - interval '1 second' WHERE id = $1")
+        .bind(&id)
+        .execute(&state.db)
+        .await
+        .unwrap();
+    let response = send(&state, get(&format!("/{}", id))).await;
+    assert_eq!(response.status(), StatusCode::GONE);
+}
+
+#[tokio::test]
+async fn past_or_conflicting_expiry_is_refused() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let past = (Utc::now() - Duration::hours(1)).to_rfc3339();
+    let future = (Utc::now() + Duration::hours(1)).to_rfc3339();
+    for body in [
+        json!({"url": "https://example.com/past", "expires_at": past}),
+        json!({"url": "https://example.com/both", "expires_at": future, "ttl_seconds": 60}),
+        json!({"url": "https://example.com/forever", "expires_at": future, "permanent": true}),
+    ] {
+        let response = send(&state, post_json("/", body)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
+
+#[tokio::test]
+async fn post_to_a_308_link_is_redirected_with_308() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let id = create(
+        &state,
+        json!({"url": "https://api.example.com/hook", "status": 308}),
+    )
+    .await;
+
+    let request = post_json(&format!("/{}", id), json!({"event": "ping"}));
+    let response = send(&state, request).await;
+    // Unlike 301 and 302, 307 and 308 forbid clients from switching to GET.
+    assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(location(&response), "https://api.example.com/hook");
+    assert_eq!(state.link_stats("", &id).await.unwrap().clicks, 1);
+}
+
+#[tokio::test]
+async fn remaining_uses_count_down() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let id = create(
+        &state,
+        json!({"url": "https://example.com/countdown", "max_uses": 3}),
+    )
+    .await;
+
+    for remaining in ["2", "1", "0"] {
+        let response = send(&state, get(&format!("/{}", id))).await;
+        assert_eq!(response.headers()[REMAINING_USES_HEADER], remaining);
+    }
+}
+
+#[tokio::test]
+async fn suffix_forwarding_appends_the_rest_of_the_path() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let id = create(
+        &state,
+        json!({"url": "https://example.com/docs/", "suffix_forward": true}),
+    )
+    .await;
+
+    for (path, url) in [
+        (format!("/{}", id), "https://example.com/docs/"),
+        (format!("/{}/guide", id), "https://example.com/docs/guide"),
+        (
+            format!("/{}/guide/intro?q=1", id),
+            "https://example.com/docs/guide/intro?q=1",
+        ),
+    ] {
+        let response = send(&state, get(&path)).await;
+        assert_eq!(response.status(), StatusCode::FOUND, "{}", path);
+        assert_eq!(location(&response), url);
+    }
+}
+
+#[tokio::test]
+async fn head_on_a_forwarded_suffix_uses_nothing_up() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let id = create(
+        &state,
+        json!({"url": "https://example.com/docs/", "suffix_forward": true, "max_uses": 1}),
+    )
+    .await;
+
+    for (path, url) in [
+        (format!("/{}/guide", id), "https://example.com/docs/guide"),
+        (
+            format!("/{}/guide/intro", id),
+            "https://example.com/docs/guide/intro",
+        ),
+    ] {
+        let request = HttpRequest::head(&path).body(Body::empty()).unwrap();
+        let response = send(&state, request).await;
+        assert_eq!(response.status(), StatusCode::FOUND, "{}", path);
+        assert_eq!(location(&response), url);
+    }
+    assert_eq!(uses(&state, &id).await, 0);
+    let response = send(&state, get(&format!("/{}/guide/intro", id))).await;
+    assert_eq!(location(&response), "https://example.com/docs/guide/intro");
+}
+
+#[tokio::test]
+async fn plain_links_do_not_forward_suffixes() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let id = create(&state, json!({"url": "https://example.com/plain"})).await;
+
+    let response = send(&state, get(&format!("/{}/guide", id))).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn passthrough_is_an_alias_of_suffix_forward() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let id = create(
+        &state,
+        json!({"url": "https://example.com/docs/", "passthrough": true}),
+    )
+    .await;
+
+    let response = send(&state, get(&format!("/{}/guide", id))).await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(location(&response), "https://example.com/docs/guide");
+}
+
+#[tokio::test]
+async fn default_ttl_applies_unless_permanent() {
+    let Some(state) = test_state(|config| {
+        config.default_ttl = Some(StdDuration::from_secs(3600));
+    })
+    .await
+    else {
+        return;
+    };
+    let id = create(&state, json!({"url": "https://example.com/default"})).await;
+    let expires_at = state.link_stats("", &id).await.unwrap().expires_at.unwrap();
+    let left = (expires_at - Utc::now()).num_seconds();
+    assert!((3590..=3600).contains(&left), "{}", left);
+
+    let id = create(
+        &state,
+        json!({"url": "https://example.com/kept", "permanent": true}),
+    )
+    .await;
+    assert!(state
+        .link_stats("", &id)
+        .await
+        .unwrap()
+        .expires_at
+        .is_none());
+}
+
+/// Shortens `url` as if over a connection from `peer` forwarding for
+/// `forwarded_for`, and returns the recorded creator address.
+async fn created_ip(state: &AppState, peer: &str, forwarded_for: &str, url: &str) -> Value {
+    let mut request = with_key(post_json("/v1/shorten", json!({ "url": url })), "kr");
+    request.headers_mut().insert(
+        "x-forwarded-for",
+        HeaderValue::from_str(forwarded_for).unwrap(),
+    );
+    let peer: SocketAddr = format!("{}:40000", peer).parse().unwrap();
+    request.extensions_mut().insert(ConnectInfo(peer));
+    let response = send(state, request).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let id = body_json(response).await["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let request = get(&format!("/v1/admin/links/{}", id));
+    let response = send(state, with_key(request, "kr")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    body_json(response).await["created_ip"].clone()
+}
+
+#[tokio::test]
+async fn created_ip_is_taken_from_a_trusted_proxy() {
+    let Some(state) = test_state(|config| {
+        config.trusted_proxies = vec![IpNet::parse("10.0.0.0/8").unwrap()];
+        config.api_keys = vec![ApiKey::parse("root:kr")];
+        config.admin_owners = vec!["root".to_string()];
+    })
+    .await
+    else {
+        return;
+    };
+    let ip = created_ip(&state, "10.0.0.5", "203.0.113.7", "https://example.com/via").await;
+    assert_eq!(ip, "203.0.113.7");
+}
+
+#[tokio::test]
+async fn created_ip_ignores_forwarding_from_an_untrusted_peer() {
+    let Some(state) = test_state(|config| {
+        config.trusted_proxies = vec![IpNet::parse("10.0.0.0/8").unwrap()];
+        config.api_keys = vec![ApiKey::parse("root:kr")];
+        config.admin_owners = vec!["root".to_string()];
+    })
+    .await
+    else {
+        return;
+    };
+    let ip = created_ip(
+        &state,
+        "198.51.100.9",
+        "203.0.113.7",
+        "https://example.com/direct",
+    )
+    .await;
+    assert_eq!(ip, "198.51.100.9");
+}
+
+#[tokio::test]
+async fn link_writes_are_audited_with_their_actor() {
+    let Some(state) = test_state(|config| {
+        config.api_keys = vec![ApiKey::parse("alice:ka")];
+    })
+    .await
+    else {
+        return;
+    };
+    let request = post_json("/v1/shorten", json!({"url": "https://example.com/audited"}));
+    let response = send(&state, with_key(request, "ka")).await;
+    let id = body_json(response).await["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    send(&state, with_key(get(&format!("/{}", id)), "ka")).await;
+    let request = HttpRequest::delete(format!("/v1/links/{}", id))
+        .body(Body::empty())
+        .unwrap();
+    send(&state, with_key(request, "ka")).await;
+
+    let entries: Vec<(Option<String>, String, Option<String>)> =
+        sqlx::query_as("SELECT actor, action, link_id FROM audit_log ORDER BY id")
+            .fetch_all(&state.db)
+            .await
+            .unwrap();
+    let alice = Some("alice".to_string());
+    assert_eq!(
+        entries,
+        [
+            (alice.clone(), "create".to_string(), Some(id.clone())),
+            (alice, "delete".to_string(), Some(id)),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn batch_reports_each_entry() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let batch = json!([{"url": "https://example.com/good"}, {"url": "not a url"}]);
+
+    let response = send(&state, post_json("/v1/shorten/batch", batch)).await;
+    assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+    let results = body_json(response).await;
+    assert_eq!(results[0]["status"], 201);
+    assert!(results[0]["id"].is_string());
+    assert_eq!(results[1]["status"], 400);
+    assert_eq!(results[1]["input"], "not a url");
+    assert!(results[1]["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("Invalid url"));
+}
+
+#[tokio::test]
+async fn imported_ids_resolve_without_a_check_character() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    // Long enough to be checked if it were generated, which it isn't.
+    let id = "docs-from-elsewhere";
+    assert!(!state.has_valid_checksum(id));
+    let rows = json!([{ "id": id, "url": "https://example.com/imported" }]);
+    let response = send(&state, post_json("/v1/import", rows)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_json(response).await["inserted"], 1);
+
+    let response = send(&state, get(&format!("/{}", id))).await;
+    assert_eq!(location(&response), "https://example.com/imported");
+    let (is_alias,): (bool,) = sqlx::query_as("SELECT is_alias FROM urls WHERE id = $1")
+        .bind(id)
+        .fetch_one(&state.db)
+        .await
+        .unwrap();
+    assert!(is_alias);
+}
+
+#[tokio::test]
+async fn batches_over_the_limit_are_refused_whole() {
+    let Some(state) = test_state(|config| config.max_batch_items = 2).await else {
+        return;
+    };
+    let batch: Vec<Value> = (0..3)
+        .map(|i| json!({ "url": format!("https://example.com/batch/{}", i) }))
+        .collect();
+    let response = send(&state, post_json("/v1/shorten/batch", json!(batch))).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(
+        body_json(response).await["error"],
+        "At most 2 entries are accepted per request"
+    );
+
+    let rows: Vec<Value> = (0..3)
+        .map(|i| json!({ "id": format!("imp{}", i), "url": format!("https://example.com/import/{}", i) }))
+        .collect();
+    let response = send(&state, post_json("/v1/import", json!(rows))).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let (links,): (i64,) = sqlx::query_as("SELECT count(*) FROM urls")
+        .fetch_one(&state.db)
+        .await
+        .unwrap();
+    assert_eq!(links, 0);
+}
+
+#[tokio::test]
+async fn trailing_slash_and_case_resolve_alike() {
+    let Some(state) = test_state(|config| {
+        config.trim_trailing_slash = true;
+        config.case_insensitive_ids = true;
+    })
+    .await
+    else {
+        return;
+    };
+    let id = create(&state, json!({"url": "https://example.com/slash"})).await;
+
+    for path in [
+        format!("/{}", id),
+        format!("/{}/", id),
+        format!("/{}/", id.to_uppercase()),
+    ] {
+        let response = send(&state, get(&path)).await;
+        assert_eq!(response.status(), StatusCode::FOUND, "{}", path);
+        assert_eq!(location(&response), "https://example.com/slash");
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn aliases_differing_in_case_race_to_one_link() {
+    let Some(state) = test_state(|config| config.case_insensitive_ids = true).await else {
+        return;
+    };
+    for round in 0..8 {
+        let tasks: Vec<_> = ["Race", "rACE"]
+            .into_iter()
+            .map(|alias| {
+                let state = state.clone();
+                let body = json!({
+                    "url": format!("https://example.com/{}/{}", round, alias),
+                    "namespace": format!("round{}", round),
+                    "alias": alias,
+                });
+                tokio::spawn(async move { send(&state, post_json("/", body)).await.status() })
+            })
+            .collect();
+        let mut statuses = Vec::new();
+        for task in tasks {
+            statuses.push(task.await.unwrap());
+        }
+        statuses.sort();
+        assert_eq!(statuses, [StatusCode::CREATED, StatusCode::CONFLICT]);
+    }
+}
+
+#[tokio::test]
+async fn id_length_is_bounded() {
+    let Some(state) = test_state(|config| {
+        config.id_length_min = 4;
+        config.id_length_max = 32;
+    })
+    .await
+    else {
+        return;
+    };
+    for len in [4, 32] {
+        let url = format!("https://example.com/{}", len);
+        let id = create(&state, json!({"url": url, "id_length": len})).await;
+        assert_eq!(id.len(), len);
+    }
+    for len in [3, 33] {
+        let url = format!("https://example.com/{}", len);
+        let response = send(
+            &state,
+            post_json("/", json!({"url": url, "id_length": len})),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
+
+#[tokio::test]
+async fn validate_reports_without_storing() {
+    let Some(state) = test_state(|config| config.block_private_hosts = true).await else {
+        return;
+    };
+    let response = send(
+        &state,
+        post_json("/v1/validate", json!({"url": "http://10.0.0.5/"})),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = body_json(response).await;
+    assert_eq!(body["valid"], false);
+    assert!(body["reason"]
+        .as_str()
+        .unwrap()
+        .starts_with("Destination host is not allowed"));
+
+    let response = send(
+        &state,
+        post_json("/v1/validate", json!({"url": "http://1.1.1.1/ok"})),
+    )
+    .await;
+    let body = body_json(response).await;
+    assert_eq!(body["valid"], true);
+    assert!(body["id"].is_string());
+
+    let response = send(&state, get("/v1/stats/summary")).await;
+    assert_eq!(body_json(response).await["total_links"], 0);
+}
+
+#[cfg(feature = "client")]
+#[tokio::test]
+async fn client_shortens_and_resolves() {
+    use crate::client::{ClientError, ShortenerClient};
+
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let addr = serve(build_router(state.clone())).await;
+    let client = ShortenerClient::new(&format!("http://{}", addr), None).unwrap();
+
+    let created = client.shorten("https://example.com/client").await.unwrap();
+    assert_eq!(created.original_url, "https://example.com/client");
+    let url = client.resolve(&created.id).await.unwrap();
+    assert_eq!(url, "https://example.com/client");
+    assert_eq!(state.link_stats("", &created.id).await.unwrap().clicks, 0);
+
+    match client.shorten("not a url").await {
+        Err(ClientError::Api { status, .. }) => assert_eq!(status, 400),
+        other => panic!("{:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn same_alias_lives_in_each_namespace() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    for ns in ["alpha", "beta"] {
+        let url = format!("https://example.com/{}", ns);
+        create(
+            &state,
+            json!({"url": url, "alias": "docs", "namespace": ns}),
+        )
+        .await;
+    }
+
+    for ns in ["alpha", "beta"] {
+        let response = send(&state, get(&format!("/{}/docs", ns))).await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(location(&response), format!("https://example.com/{}", ns));
+    }
+    let response = send(&state, get("/docs")).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn if_absent_returns_the_link_holding_the_alias() {
+    let Some(state) = test_state(|config| {
+        config.api_keys = vec![
+            ApiKey::parse("root:kr"),
+            ApiKey::parse("alice:ka"),
+            ApiKey::parse("bob:kb"),
+        ];
+        config.admin_owners = vec!["root".to_string()];
+    })
+    .await
+    else {
+        return;
+    };
+    let first = json!({"url": "https://example.com/first", "alias": "taken"});
+    let response = send(&state, with_key(post_json("/", first), "ka")).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = json!({"url": "https://example.com/second", "alias": "taken"});
+
+    let response = send(&state, with_key(post_json("/", body.clone()), "ka")).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    // Only its owner, or an admin, gets the link back.
+    for key in ["ka", "kr"] {
+        let request = with_key(post_json("/?if_absent=true", body.clone()), key);
+        let response = send(&state, request).await;
+        assert_eq!(response.status(), StatusCode::OK, "{}", key);
+        assert_eq!(response.headers()[SHORT_ID_HEADER], "taken");
+        assert_eq!(
+            body_json(response).await["original_url"],
+            "https://example.com/first"
+        );
+    }
+    let request = with_key(post_json("/?if_absent=true", body), "kb");
+    let response = send(&state, request).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert!(!response.headers().contains_key(SHORT_ID_HEADER));
+}
+
+#[tokio::test]
+async fn allowlist_admits_only_listed_domains() {
+    let Some(state) = test_state(|config| {
+        config.allowed_domains = vec!["example.com".to_string()];
+        config.block_private_hosts = false;
+    })
+    .await
+    else {
+        return;
+    };
+    for url in ["https://example.com/", "https://docs.example.com/"] {
+        create(&state, json!({ "url": url })).await;
+    }
+    for url in ["https://evil.test/", "https://notexample.com/"] {
+        let response = send(&state, post_json("/", json!({ "url": url }))).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", url);
+    }
+}
+
+#[tokio::test]
+async fn alias_for_an_already_shortened_url_names_the_existing_id() {
+    let Some(state) = test_state(|config| config.dedupe_urls = true).await else {
+        return;
+    };
+    let existing = create(&state, json!({"url": "https://example.com/once"})).await;
+
+    let response = send(
+        &state,
+        post_json(
+            "/",
+            json!({"url": "https://example.com/once", "alias": "fresh"}),
+        ),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let error = body_json(response).await["error"].to_string();
+    assert!(error.contains(&existing), "{}", error);
+    let response = send(&state, get("/fresh")).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn robots_txt_is_served_as_configured() {
+    let Some(state) = test_state(|config| {
+        config.robots_txt = "User-agent: *\nAllow: /\n".to_string();
+    })
+    .await
+    else {
+        return;
+    };
+    let response = send(&state, get("/robots.txt")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()[CONTENT_TYPE]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    assert_eq!(body_bytes(response).await, "User-agent: *\nAllow: /\n");
+}
+
+#[tokio::test]
+async fn keyspace_fill_is_reported_and_warned_about() {
+    let Some(state) = test_state(|config| {
+        config.id_length = 4;
+        config.keyspace_warn_ratio = 1e-7;
+    })
+    .await
+    else {
+        return;
+    };
+    for i in 0..2 {
+        let url = format!("https://example.com/keyspace/{}", i);
+        assert_eq!(create(&state, json!({ "url": url })).await.len(), 4);
+    }
+    assert_eq!(state.keyspace_warnings.load(Ordering::Relaxed), 0);
+
+    let mut conn = state.db.acquire().await.unwrap();
+    state.check_keyspace(&mut conn).await.unwrap();
+    assert_eq!(state.keyspace_warnings.load(Ordering::Relaxed), 1);
+    let response = send(&state, get("/metrics")).await;
+    let body = String::from_utf8(body_bytes(response).await.to_vec()).unwrap();
+    let fill = 2.0 / keyspace_size(4, false);
+    assert!(
+        body.contains(&format!("shortener_id_keyspace_fill_ratio {}\n", fill)),
+        "{}",
+        body
+    );
+}
+
+#[tokio::test]
+async fn tables_of_another_name_keep_their_links_apart() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let tenant = AppState::try_new(AppConfig {
+        db_url: state.config.db_url.clone(),
+        db_replica_url: None,
+        tables: Tables::new("tenant1"),
+        ..AppConfig::from_env()
+    })
+    .await
+    .unwrap();
+    let id = create(&state, json!({"url": "https://example.com/default"})).await;
+    let other = create(&tenant, json!({"url": "https://example.com/tenant"})).await;
+
+    let response = send(&tenant, get(&format!("/{}", id))).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = send(&tenant, get(&format!("/{}", other))).await;
+    assert_eq!(location(&response), "https://example.com/tenant");
+    let (clicks,): (i64,) = sqlx::query_as("SELECT count(*) FROM tenant1_clicks")
+        .fetch_one(&state.db)
+        .await
+        .unwrap();
+    assert_eq!(clicks, 1);
+}
+
+#[tokio::test]
+async fn migrations_are_versioned_per_table_and_can_be_skipped() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let tenant_config = |table: &str, run_migrations| AppConfig {
+        db_url: state.config.db_url.clone(),
+        db_replica_url: None,
+        tables: Tables::new(table),
+        run_migrations,
+        ..AppConfig::from_env()
+    };
+    assert!(AppState::try_new(tenant_config("unmanaged", false))
+        .await
+        .is_err());
+    AppState::try_new(tenant_config("tenant1", true))
+        .await
+        .unwrap();
+    // Starting again finds nothing left to do.
+    AppState::try_new(tenant_config(DEFAULT_TABLE, true))
+        .await
+        .unwrap();
+    AppState::try_new(tenant_config("tenant1", false))
+        .await
+        .unwrap();
+
+    let versions: Vec<(i64,)> =
+        sqlx::query_as("SELECT version FROM _sqlx_migrations ORDER BY version")
+            .fetch_all(&state.db)
+            .await
+            .unwrap();
+    assert_eq!(versions.len(), 4);
+    assert_eq!(versions[..2], [(1,), (2,)]);
+    assert!(versions[2].0 > MIGRATION_VERSION_STRIDE);
+    let (tables,): (i64,) = sqlx::query_as(
+        "SELECT count(*) FROM pg_tables WHERE schemaname = current_schema() AND tablename LIKE 'unmanaged%'",
+    )
+    .fetch_one(&state.db)
+    .await
+    .unwrap();
+    assert_eq!(tables, 0);
+}
+
+#[tokio::test]
+async fn recent_shows_only_hosts_newest_first() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let older = create(&state, json!({"url": "https://old.example.com/secret"})).await;
+    let newer = create(&state, json!({"url": "https://new.example.com/secret"})).await;
+    sqlx::query("UPDATE urls SET created_at = created_at - interval '1 hour' WHERE id = $1")
+        .bind(&older)
+        .execute(&state.db)
+        .await
+        .unwrap();
+
+    let response = send(&state, get("/recent?n=1")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let links = body_json(response).await;
+    assert_eq!(links.as_array().unwrap().len(), 1);
+    assert_eq!(links[0]["id"], newer.as_str());
+    assert_eq!(links[0]["host"], "new.example.com");
+    assert!(!links.to_string().contains("secret"));
+}
+
+#[tokio::test]
+async fn qr_codes_come_as_png_svg_or_data_uri() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let id = create(&state, json!({"url": "https://example.com/qr"})).await;
+
+    let response = send(&state, get(&format!("/{}/qr", id))).await;
+    assert_eq!(response.headers()[CONTENT_TYPE], "image/png");
+    let response = send(&state, get(&format!("/{}/qr?format=svg", id))).await;
+    assert_eq!(response.headers()[CONTENT_TYPE], "image/svg+xml");
+    let svg = String::from_utf8(body_bytes(response).await.to_vec()).unwrap();
+    assert!(svg.contains("<svg"));
+    let response = send(&state, get(&format!("/{}/qr?format=datauri", id))).await;
+    assert_eq!(response.headers()[CONTENT_TYPE], "text/plain");
+    let uri = String::from_utf8(body_bytes(response).await.to_vec()).unwrap();
+    assert!(uri.starts_with("data:image/png;base64,"));
+}
+
+#[tokio::test]
+async fn put_creates_then_replaces_the_link() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let response = send(
+        &state,
+        put_json("/chosen", json!({"url": "https://example.com/v1"})),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = send(
+        &state,
+        put_json("/chosen", json!({"url": "https://example.com/v2"})),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = send(&state, get("/chosen")).await;
+    assert_eq!(location(&response), "https://example.com/v2");
+}
+
+#[tokio::test]
+async fn redirect_outcomes_are_counted() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let live = create(&state, json!({"url": "https://example.com/live"})).await;
+    let expired = create(&state, json!({"url": "https://example.com/expired"})).await;
+    let disabled = create(&state, json!({"url": "https://example.com/disabled"})).await;
+    sqlx::query("UPDATE urls SET expires_at = now() - interval '1 second' WHERE id = $1")
+        .bind(&expired)
+        .execute(&state.db)
+        .await
+        .unwrap();
+    let request = HttpRequest::patch(format!("/v1/links/{}", disabled))
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({"enabled": false}).to_string()))
+        .unwrap();
+    send(&state, request).await;
+
+    for (id, status) in [
+        (live.as_str(), StatusCode::FOUND),
+        ("nope12", StatusCode::NOT_FOUND),
+        (expired.as_str(), StatusCode::GONE),
+        (disabled.as_str(), StatusCode::FORBIDDEN),
+    ] {
+        let response = send(&state, get(&format!("/{}", id))).await;
+        assert_eq!(response.status(), status);
+    }
+
+    let response = send(&state, get("/metrics")).await;
+    let metrics = String::from_utf8(body_bytes(response).await.to_vec()).unwrap();
+    for outcome in ["redirected", "not_found", "expired", "disabled"] {
+        let line = format!("shortener_redirects_total{{outcome=\"{}\"}} 1\n", outcome);
+        assert!(metrics.contains(&line), "missing {}", line);
+    }
+}
+
+#[tokio::test]
+async fn new_url_is_201_and_known_url_200() {
+    let Some(state) = test_state(|config| config.dedupe_urls = true).await else {
+        return;
+    };
+    let body = json!({"url": "https://example.com/status"});
+
+    let response = send(&state, post_json("/", body.clone())).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = send(&state, post_json("/", body)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().contains_key(LOCATION));
+}
+
+#[tokio::test]
+async fn interstitial_is_shown_to_browsers_only() {
+    let Some(state) = test_state(|config| config.interstitial = true).await else {
+        return;
+    };
+    let id = create(&state, json!({"url": "https://example.com/page"})).await;
+
+    let request = HttpRequest::get(format!("/{}", id))
+        .header(ACCEPT, "text/html,application/xhtml+xml")
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&state, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CACHE_CONTROL], "no-store");
+    let page = String::from_utf8(body_bytes(response).await.to_vec()).unwrap();
+    assert!(page.contains(r#"<a href="https://example.com/page">"#));
+    assert!(page.contains(&format!("/{}", id)));
+
+    let response = send(&state, get(&format!("/{}", id))).await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(location(&response), "https://example.com/page");
+}
+
+#[tokio::test]
+async fn bulk_delete_takes_ids_or_an_age() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let first = create(&state, json!({"url": "https://example.com/1"})).await;
+    let second = create(&state, json!({"url": "https://example.com/2"})).await;
+    let old = create(&state, json!({"url": "https://example.com/old"})).await;
+    let kept = create(&state, json!({"url": "https://example.com/kept"})).await;
+
+    let body = json!({"ids": [first, second, "nope12"]});
+    let response = send(&state, post_json("/v1/links/delete", body)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_json(response).await["deleted"], 2);
+    for id in [&first, &second] {
+        let response = send(&state, get(&format!("/{}", id))).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    sqlx::query("UPDATE urls SET created_at = '2000-01-01' WHERE id = $1")
+        .bind(&old)
+        .execute(&state.db)
+        .await
+        .unwrap();
+    let body = json!({"older_than": "2001-01-01"});
+    let response = send(&state, post_json("/v1/links/delete", body)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_json(response).await["deleted"], 1);
+    let response = send(&state, get(&format!("/{}", old))).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = send(&state, get(&format!("/{}", kept))).await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+
+    let body = json!({"ids": [kept], "older_than": "2001-01-01"});
+    let response = send(&state, post_json("/v1/links/delete", body)).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn accept_picks_plain_text_or_json() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let shorten = |accept: &'static str, path: &'static str| {
+        let state = state.clone();
+        async move {
+            let url = format!("https://example.com/{}", path);
+            let mut request = post_json("/", json!({ "url": url }));
+            request
+                .headers_mut()
+                .insert(ACCEPT, HeaderValue::from_static(accept));
+            send(&state, request).await
+        }
+    };
+
+    let response = shorten("text/plain", "plain").await;
+    assert_eq!(
+        response.headers()[CONTENT_TYPE],
+        "text/plain; charset=utf-8"
+    );
+    let short_url = location(&response).to_string();
+    let body = String::from_utf8(body_bytes(response).await.to_vec()).unwrap();
+    assert_eq!(body, format!("{}\n", short_url));
+
+    let response = shorten("application/json", "json").await;
+    assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+    let short_url = location(&response).to_string();
+    assert_eq!(body_json(response).await["short_url"], short_url.as_str());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrent_redirects_are_all_counted() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let id = create(&state, json!({"url": "https://example.com/busy"})).await;
+
+    let tasks: Vec<_> = (0..50)
+        .map(|_| {
+            let (state, path) = (state.clone(), format!("/{}", id));
+            tokio::spawn(async move { send(&state, get(&path)).await.status() })
+        })
+        .collect();
+    for task in tasks {
+        assert_eq!(task.await.unwrap(), StatusCode::FOUND);
+    }
+
+    assert_eq!(state.link_stats("", &id).await.unwrap().clicks, 50);
+    let (clicks,): (i64,) = sqlx::query_as("SELECT count(*) FROM clicks")
+        .fetch_one(&state.db)
+        .await
+        .unwrap();
+    assert_eq!(clicks, 50);
+}
+
+#[tokio::test]
+async fn redirecting_url_is_stored_as_its_destination() {
+    let Some(state) = test_state(|config| {
+        config.resolve_redirects = true;
+        config.block_private_hosts = false;
+        config.allowed_domains.clear();
+    })
+    .await
+    else {
+        return;
+    };
+    let hop = Router::new().route(
+        "/start",
+        routing::get(|| async {
+            (
+                StatusCode::MOVED_PERMANENTLY,
+                [(LOCATION, "https://example.com/final")],
+            )
+        }),
+    );
+    let addr = serve(hop).await;
+
+    let url = format!("http://{}/start", addr);
+    let response = send(&state, post_json("/", json!({ "url": url }))).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(
+        body_json(response).await["original_url"],
+        "https://example.com/final"
+    );
+}
+
+#[tokio::test]
+async fn requests_are_access_logged_with_latency() {
+    let Some(state) = test_state(|_| {}).await else {
+        return;
+    };
+    let buffer = Buffer::default();
+    let subscriber = tracing_subscriber::fmt()
+        .json()
+        .with_writer(buffer.clone())
+        .finish();
+
+    let request = HttpRequest::get("/robots.txt")
+        .header(REQUEST_ID_HEADER, "req-1")
+        .body(Body::empty())
+        .unwrap();
+    let guard = tracing::subscriber::set_default(subscriber);
+    let response = send(&state, request).await;
+    drop(guard);
+    assert_eq!(response.headers()[REQUEST_ID_HEADER], "req-1");
+
+    let events: Vec<Value> = buffer
+        .contents()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let access = events
+        .iter()
+        .find(|event| event["target"] == "access")
+        .unwrap();
+    assert_eq!(access["fields"]["status"], 200);
+    assert_eq!(access["fields"]["path"], "/robots.txt");
+    assert_eq!(access["fields"]["request_id"], "req-1");
+    assert!(access["fields"]["latency_ms"].is_f64());
+}
+
+#[tokio::test]
+async fn management_token_guards_anonymous_changes() {
+    let Some(state) = test_state(|config| {
+        config.api_keys.clear();
+        config.dedupe_urls = true;
+        config.manage_key = Some(blake3::derive_key(MANAGE_TOKEN_CONTEXT, b"secret"));
+    })
+    .await
+    else {
+        return;
+    };
+    let response = send(
+        &state,
+        post_json("/", json!({"url": "https://example.com/mine"})),
+    )
+    .await;
+    let body = body_json(response).await;
+    let (id, token) = (
+        body["id"].as_str().unwrap(),
+        body["manage_token"].as_str().unwrap(),
+    );
+    let other = create(&state, json!({"url": "https://example.com/theirs"})).await;
+    // Shortening the url again must not hand out its token.
+    let response = send(
+        &state,
+        post_json("/", json!({"url": "https://example.com/mine"})),
+    )
+    .await;
+    assert!(body_json(response).await.get("manage_token").is_none());
+
+    let with_token = |mut request: HttpRequest<Body>, token: &str| {
+        let value = HeaderValue::from_str(token).unwrap();
+        request.headers_mut().insert(MANAGE_TOKEN_HEADER, value);
+        request
+    };
+    let delete = |id: &str| {
+        HttpRequest::delete(format!("/v1/links/{}", id))
+            .body(Body::empty())
+            .unwrap()
+    };
+    let update = || {
+        put_json(
+            &format!("/v1/links/{}", id),
+            json!({"url": "https://example.com/moved"}),
+        )
+    };
+    assert_eq!(send(&state, update()).await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(
+        send(&state, delete(id)).await.status(),
+        StatusCode::FORBIDDEN
+    );
+    let response = send(&state, with_token(delete(&other), token)).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = send(&state, with_token(update(), token)).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = send(&state, with_token(delete(id), token)).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn root_serves_a_landing_page_instead_of_405() {
+    let Some(state) = test_state(|config| config.root_redirect = None).await else {
+        return;
+    };
+    let response = send(&state, get("/")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = body_json(response).await;
+    assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(body["docs"], "/swagger-ui");
+
+    let request = HttpRequest::get("/")
+        .header(ACCEPT, "text/html")
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&state, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let page = String::from_utf8(body_bytes(response).await.to_vec()).unwrap();
+    assert!(page.contains("/swagger-ui"), "{}", page);
+}
+
+#[tokio::test]
+async fn alias_is_free_again_after_hard_delete_or_purge() {
+    let Some(state) = test_state(|config| {
+        config.api_keys.clear();
+        config.manage_key = None;
+        config.hard_delete = true;
+    })
+    .await
+    else {
+        return;
+    };
+    let alias = |url: &str| json!({"url": url, "alias": "reused"});
+    create(&state, alias("https://example.com/first")).await;
+    let request = HttpRequest::delete("/v1/links/reused")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(send(&state, request).await.status(), StatusCode::NO_CONTENT);
+
+    create(&state, alias("https://example.com/second")).await;
+    let response = send(&state, get("/reused")).await;
+    assert_eq!(location(&response), "https://example.com/second");
+
+    sqlx::query("UPDATE urls SET expires_at = now() - interval '1 second' WHERE id = 'reused'")
+        .execute(&state.db)
+        .await
+        .unwrap();
+    assert_eq!(state.purge_expired().await.unwrap(), 1);
+    create(&state, alias("https://example.com/third")).await;
+    let response = send(&state, get("/reused")).await;
+    assert_eq!(location(&response), "https://example.com/third");
+}